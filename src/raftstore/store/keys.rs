@@ -28,11 +28,13 @@ pub const EMPTY_KEY: &'static [u8] = &[];
 // local is in (0x01, 0x02);
 pub const LOCAL_PREFIX: u8 = 0x01;
 pub const LOCAL_MIN_KEY: &'static [u8] = &[LOCAL_PREFIX];
+// Equivalent to `next_key(LOCAL_MIN_KEY)`, inlined because consts can't call functions.
 pub const LOCAL_MAX_KEY: &'static [u8] = &[LOCAL_PREFIX + 1];
 
 pub const DATA_PREFIX: u8 = b'z';
 pub const DATA_PREFIX_KEY: &'static [u8] = &[DATA_PREFIX];
 pub const DATA_MIN_KEY: &'static [u8] = &[DATA_PREFIX];
+// Equivalent to `next_key(DATA_PREFIX_KEY)`, inlined because consts can't call functions.
 pub const DATA_MAX_KEY: &'static [u8] = &[DATA_PREFIX + 1];
 
 // Following keys are all local keys, so the first byte must be 0x01.
@@ -46,6 +48,7 @@ pub const REGION_RAFT_PREFIX_KEY: &'static [u8] = &[LOCAL_PREFIX, REGION_RAFT_PR
 pub const REGION_META_PREFIX: u8 = 0x03;
 pub const REGION_META_PREFIX_KEY: &'static [u8] = &[LOCAL_PREFIX, REGION_META_PREFIX];
 pub const REGION_META_MIN_KEY: &'static [u8] = &[LOCAL_PREFIX, REGION_META_PREFIX];
+// Equivalent to `next_key(REGION_META_PREFIX_KEY)`, inlined because consts can't call functions.
 pub const REGION_META_MAX_KEY: &'static [u8] = &[LOCAL_PREFIX, REGION_META_PREFIX + 1];
 
 // Following are the suffix after the local prefix.
@@ -65,13 +68,20 @@ fn make_region_id_key(region_id: u64, suffix: u8, extra_cap: usize) -> Vec<u8> {
     let mut key = Vec::with_capacity(REGION_RAFT_PREFIX_KEY.len() + mem::size_of::<u64>() +
                                      mem::size_of::<u8>() +
                                      extra_cap);
-    key.extend_from_slice(REGION_RAFT_PREFIX_KEY);
-    // no need check error here, can't panic;
-    key.write_u64::<BigEndian>(region_id).unwrap();
-    key.push(suffix);
+    make_region_id_key_into(&mut key, region_id, suffix);
     key
 }
 
+/// Write the `REGION_RAFT_PREFIX_KEY` + region id + suffix key into `buf`,
+/// reusing its existing allocation.
+fn make_region_id_key_into(buf: &mut Vec<u8>, region_id: u64, suffix: u8) {
+    buf.clear();
+    buf.extend_from_slice(REGION_RAFT_PREFIX_KEY);
+    // no need check error here, can't panic;
+    buf.write_u64::<BigEndian>(region_id).unwrap();
+    buf.push(suffix);
+}
+
 pub fn region_raft_prefix(region_id: u64) -> Vec<u8> {
     let mut key = Vec::with_capacity(REGION_RAFT_PREFIX_KEY.len() + mem::size_of::<u64>());
     key.extend_from_slice(REGION_RAFT_PREFIX_KEY);
@@ -92,14 +102,34 @@ pub fn raft_log_key(region_id: u64, log_index: u64) -> Vec<u8> {
     key
 }
 
+/// Write the raft log key for `(region_id, log_index)` into `buf`, reusing
+/// its existing allocation. Equivalent to `raft_log_key`, but avoids an
+/// allocation per call when producing many keys in a row (e.g. on the raft
+/// apply/append hot path).
+pub fn raft_log_key_into(buf: &mut Vec<u8>, region_id: u64, log_index: u64) {
+    make_region_id_key_into(buf, region_id, RAFT_LOG_SUFFIX);
+    // no need check error here, can't panic;
+    buf.write_u64::<BigEndian>(log_index).unwrap();
+}
+
 pub fn raft_state_key(region_id: u64) -> Vec<u8> {
     make_region_id_key(region_id, RAFT_STATE_SUFFIX, 0)
 }
 
+/// See `raft_log_key_into`.
+pub fn raft_state_key_into(buf: &mut Vec<u8>, region_id: u64) {
+    make_region_id_key_into(buf, region_id, RAFT_STATE_SUFFIX);
+}
+
 pub fn apply_state_key(region_id: u64) -> Vec<u8> {
     make_region_id_key(region_id, APPLY_STATE_SUFFIX, 0)
 }
 
+/// See `raft_log_key_into`.
+pub fn apply_state_key_into(buf: &mut Vec<u8>, region_id: u64) {
+    make_region_id_key_into(buf, region_id, APPLY_STATE_SUFFIX);
+}
+
 /// Get the log index from raft log key generated by `raft_log_key`.
 pub fn raft_log_index(key: &[u8]) -> Result<u64> {
     let expect_key_len = REGION_RAFT_PREFIX_KEY.len() + mem::size_of::<u64>() +
@@ -127,6 +157,32 @@ pub fn raft_log_prefix(region_id: u64) -> Vec<u8> {
     make_region_id_key(region_id, RAFT_LOG_SUFFIX, 0)
 }
 
+/// Get the `[start, end)` key range covering raft log indices
+/// `[low_index, high_index)` of `region_id`, suitable as a `delete_range`
+/// or iterator bound for raft log GC. `low_index == high_index` yields an
+/// empty range. Because every raft log key shares the same suffix byte
+/// (`RAFT_LOG_SUFFIX`), which sorts below `RAFT_STATE_SUFFIX` and
+/// `APPLY_STATE_SUFFIX`, the range never reaches into the region's
+/// `RAFT_STATE`/`APPLY_STATE` keys regardless of `high_index`.
+pub fn raft_log_range(region_id: u64, low_index: u64, high_index: u64) -> (Vec<u8>, Vec<u8>) {
+    (raft_log_key(region_id, low_index), raft_log_key(region_id, high_index))
+}
+
+/// Get the `[start, end)` key range covering every raft-local key
+/// (raft log, raft state, apply state) of `region_id`, suitable for
+/// deleting all of a region's raft data on region destroy.
+pub fn region_raft_range(region_id: u64) -> (Vec<u8>, Vec<u8>) {
+    let start = region_raft_prefix(region_id);
+    let end = match region_id.checked_add(1) {
+        Some(next_region_id) => region_raft_prefix(next_region_id),
+        // `region_id` is the largest possible region id, so there is no
+        // next raft prefix; fall back to the start of the next local
+        // key space, which bounds all raft data for all regions.
+        None => REGION_META_PREFIX_KEY.to_vec(),
+    };
+    (start, end)
+}
+
 fn make_region_meta_key(region_id: u64, suffix: u8) -> Vec<u8> {
     let mut key = Vec::with_capacity(REGION_META_PREFIX_KEY.len() + mem::size_of::<u64>() +
                                      mem::size_of::<u8>());
@@ -171,11 +227,55 @@ pub fn validate_data_key(key: &[u8]) -> bool {
 
 pub fn data_key(key: &[u8]) -> Vec<u8> {
     let mut v = Vec::with_capacity(DATA_PREFIX_KEY.len() + key.len());
-    v.extend_from_slice(DATA_PREFIX_KEY);
-    v.extend_from_slice(key);
+    data_key_into(&mut v, key);
     v
 }
 
+/// See `raft_log_key_into`.
+pub fn data_key_into(buf: &mut Vec<u8>, key: &[u8]) {
+    buf.clear();
+    buf.extend_from_slice(DATA_PREFIX_KEY);
+    buf.extend_from_slice(key);
+}
+
+/// A reusable key-encoding scratch buffer. Wraps a caller-owned `Vec<u8>`
+/// and overwrites it in place on each `push_*` call, so a write batch that
+/// produces many keys can share one allocation instead of allocating a
+/// fresh `Vec` per key.
+pub struct KeyBuilder<'a> {
+    buf: &'a mut Vec<u8>,
+}
+
+impl<'a> KeyBuilder<'a> {
+    pub fn new(buf: &'a mut Vec<u8>) -> KeyBuilder<'a> {
+        KeyBuilder { buf: buf }
+    }
+
+    /// Write the raft log key for `(region_id, log_index)` and return it.
+    pub fn push_raft_log(&mut self, region_id: u64, log_index: u64) -> &[u8] {
+        raft_log_key_into(self.buf, region_id, log_index);
+        self.buf
+    }
+
+    /// Write the raft state key for `region_id` and return it.
+    pub fn push_raft_state(&mut self, region_id: u64) -> &[u8] {
+        raft_state_key_into(self.buf, region_id);
+        self.buf
+    }
+
+    /// Write the apply state key for `region_id` and return it.
+    pub fn push_apply_state(&mut self, region_id: u64) -> &[u8] {
+        apply_state_key_into(self.buf, region_id);
+        self.buf
+    }
+
+    /// Write the data key for `key` and return it.
+    pub fn push_data(&mut self, key: &[u8]) -> &[u8] {
+        data_key_into(self.buf, key);
+        self.buf
+    }
+}
+
 pub fn origin_key(key: &[u8]) -> &[u8] {
     assert!(validate_data_key(key));
     &key[DATA_PREFIX_KEY.len()..]
@@ -197,15 +297,90 @@ pub fn enc_end_key(region: &Region) -> Vec<u8> {
     data_end_key(region.get_end_key())
 }
 
+/// Compute the lexicographically smallest key that is strictly greater
+/// than every key with `key` as a prefix, by incrementing the last
+/// non-`0xFF` byte and truncating everything after it. This is the
+/// general form of the `prefix + 1` trick used to derive an exclusive
+/// upper bound (e.g. `LOCAL_MAX_KEY`, `DATA_MAX_KEY`) for scanning a
+/// sub-keyspace. Returns `None` when `key` is empty or made up entirely
+/// of `0xFF` bytes, meaning the prefix has no finite upper bound.
+pub fn next_key(key: &[u8]) -> Option<Vec<u8>> {
+    match key.iter().rposition(|&b| b != 0xFF) {
+        Some(pos) => {
+            let mut next = key[..pos + 1].to_vec();
+            next[pos] += 1;
+            Some(next)
+        }
+        None => None,
+    }
+}
+
 #[inline]
 pub fn data_end_key(region_end_key: &[u8]) -> Vec<u8> {
     if region_end_key.is_empty() {
-        DATA_MAX_KEY.to_vec()
+        next_key(DATA_PREFIX_KEY).unwrap()
     } else {
         data_key(region_end_key)
     }
 }
 
+/// The kind of information encoded in an on-disk key, as produced by
+/// `classify_key`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum KeyType {
+    StoreIdent,
+    RaftLog { region_id: u64, index: u64 },
+    RaftState { region_id: u64 },
+    ApplyState { region_id: u64 },
+    RegionState { region_id: u64 },
+    Data { origin_key: Vec<u8> },
+    Unknown,
+}
+
+/// Classify an arbitrary on-disk key, dispatching to the right decoder
+/// based on its prefix and suffix layout. Unrecognized or malformed keys
+/// are reported as `KeyType::Unknown` rather than an error, so callers
+/// like debug dumps and key-space scanners can keep going.
+pub fn classify_key(key: &[u8]) -> KeyType {
+    if key == STORE_IDENT_KEY {
+        return KeyType::StoreIdent;
+    }
+
+    if key.starts_with(REGION_RAFT_PREFIX_KEY) {
+        if let Ok((region_id, index)) = decode_raft_log_key(key) {
+            return KeyType::RaftLog {
+                region_id: region_id,
+                index: index,
+            };
+        }
+
+        let suffix_idx = REGION_RAFT_PREFIX_KEY.len() + mem::size_of::<u64>();
+        if key.len() == suffix_idx + mem::size_of::<u8>() {
+            let region_id = BigEndian::read_u64(&key[REGION_RAFT_PREFIX_KEY.len()..suffix_idx]);
+            match key[suffix_idx] {
+                RAFT_STATE_SUFFIX => return KeyType::RaftState { region_id: region_id },
+                APPLY_STATE_SUFFIX => return KeyType::ApplyState { region_id: region_id },
+                _ => return KeyType::Unknown,
+            }
+        }
+
+        return KeyType::Unknown;
+    }
+
+    if key.starts_with(REGION_META_PREFIX_KEY) {
+        if let Ok((region_id, REGION_STATE_SUFFIX)) = decode_region_meta_key(key) {
+            return KeyType::RegionState { region_id: region_id };
+        }
+        return KeyType::Unknown;
+    }
+
+    if validate_data_key(key) {
+        return KeyType::Data { origin_key: origin_key(key).to_vec() };
+    }
+
+    KeyType::Unknown
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -324,4 +499,106 @@ mod tests {
         assert_eq!(enc_start_key(&region), vec![DATA_PREFIX, 1]);
         assert_eq!(enc_end_key(&region), vec![DATA_PREFIX, 2]);
     }
+
+    #[test]
+    fn test_classify_key() {
+        assert_eq!(classify_key(&store_ident_key()), KeyType::StoreIdent);
+
+        for region_id in vec![0, 1, 1024, ::std::u64::MAX] {
+            assert_eq!(classify_key(&raft_log_key(region_id, 2)),
+                       KeyType::RaftLog {
+                           region_id: region_id,
+                           index: 2,
+                       });
+            assert_eq!(classify_key(&raft_state_key(region_id)),
+                       KeyType::RaftState { region_id: region_id });
+            assert_eq!(classify_key(&apply_state_key(region_id)),
+                       KeyType::ApplyState { region_id: region_id });
+            assert_eq!(classify_key(&region_state_key(region_id)),
+                       KeyType::RegionState { region_id: region_id });
+        }
+
+        assert_eq!(classify_key(&data_key(b"abc")),
+                   KeyType::Data { origin_key: b"abc".to_vec() });
+
+        assert_eq!(classify_key(b"not a real key"), KeyType::Unknown);
+    }
+
+    #[test]
+    fn test_key_builder() {
+        let mut buf = Vec::new();
+        let mut builder = KeyBuilder::new(&mut buf);
+
+        for region_id in vec![0, 1, 1024, ::std::u64::MAX] {
+            assert_eq!(builder.push_raft_log(region_id, 2), &*raft_log_key(region_id, 2));
+            assert_eq!(builder.push_raft_state(region_id), &*raft_state_key(region_id));
+            assert_eq!(builder.push_apply_state(region_id), &*apply_state_key(region_id));
+        }
+
+        for key in vec![&b""[..], b"abc", b"z"] {
+            assert_eq!(builder.push_data(key), &*data_key(key));
+        }
+    }
+
+    #[test]
+    fn test_raft_log_range() {
+        // empty range when low == high.
+        let (start, end) = raft_log_range(1, 5, 5);
+        assert_eq!(start, end);
+
+        // sorts correctly and covers exactly [low, high).
+        let (start, end) = raft_log_range(1, 2, 8);
+        assert_eq!(start, raft_log_key(1, 2));
+        assert_eq!(end, raft_log_key(1, 8));
+        assert!(start < raft_log_key(1, 5));
+        assert!(raft_log_key(1, 5) < end);
+        assert!(raft_log_key(1, 1) < start);
+        assert!(end <= raft_log_key(1, 8));
+
+        // never overlaps the region's RAFT_STATE/APPLY_STATE keys, even
+        // when high_index is far beyond any stored index.
+        let (_, end) = raft_log_range(1, 0, ::std::u64::MAX);
+        assert!(end < raft_state_key(1));
+        assert!(end < apply_state_key(1));
+
+        // never overlaps a neighboring region.
+        let (_, end) = raft_log_range(1, 0, ::std::u64::MAX);
+        assert!(end < region_raft_prefix(2));
+    }
+
+    #[test]
+    fn test_region_raft_range() {
+        for region_id in vec![0, 1, 1024, ::std::u64::MAX - 1] {
+            let (start, end) = region_raft_range(region_id);
+            assert!(start < raft_log_key(region_id, 0));
+            assert!(raft_log_key(region_id, ::std::u64::MAX) < end);
+            assert!(raft_state_key(region_id) < end);
+            assert!(apply_state_key(region_id) < end);
+
+            // doesn't overlap the next region.
+            assert!(end <= region_raft_prefix(region_id + 1));
+        }
+
+        // the largest region id has no next prefix to bound against, but
+        // must still cover all of its own raft data.
+        let region_id = ::std::u64::MAX;
+        let (start, end) = region_raft_range(region_id);
+        assert!(start < raft_log_key(region_id, 0));
+        assert!(raft_state_key(region_id) < end);
+        assert!(apply_state_key(region_id) < end);
+    }
+
+    #[test]
+    fn test_next_key() {
+        assert_eq!(next_key(&[1, 2, 3]), Some(vec![1, 2, 4]));
+        assert_eq!(next_key(&[1, 2, 0xFF]), Some(vec![1, 3]));
+        assert_eq!(next_key(&[1, 0xFF, 0xFF]), Some(vec![2]));
+        assert_eq!(next_key(&[0xFF, 0xFF, 0xFF]), None);
+        assert_eq!(next_key(&[]), None);
+
+        // matches the hand-derived *_MAX_KEY constants.
+        assert_eq!(next_key(LOCAL_MIN_KEY), Some(LOCAL_MAX_KEY.to_vec()));
+        assert_eq!(next_key(DATA_PREFIX_KEY), Some(DATA_MAX_KEY.to_vec()));
+        assert_eq!(next_key(REGION_META_PREFIX_KEY), Some(REGION_META_MAX_KEY.to_vec()));
+    }
 }